@@ -2,41 +2,64 @@ use bril_rs as bril;
 use cranelift::frontend::{FunctionBuilder, FunctionBuilderContext, Variable};
 use cranelift::codegen::{ir, isa, settings};
 use cranelift::codegen::ir::InstBuilder;
+use cranelift::codegen::ir::condcodes::{IntCC, FloatCC};
 use cranelift::codegen::entity::EntityRef;
 use cranelift::codegen::verifier::verify_function;
+use cranelift::codegen::ir::MemFlags;
 use cranelift_object::{ObjectModule, ObjectBuilder};
+use cranelift_jit::{JITModule, JITBuilder};
 use cranelift_module::{default_libcall_names, Module};
 use cranelift_native;
 use std::collections::HashMap;
 
+/// Runtime `print` for integers, used when running under the JIT.
+extern "C" fn print_int(i: i64) {
+    println!("{}", i);
+}
+
 struct RTSigs {
-    print_int: ir::Signature
+    print_int: ir::Signature,
+    print_float: ir::Signature,
+    malloc: ir::Signature,
+    free: ir::Signature,
 }
 
 struct RTIds {
-    print_int: cranelift_module::FuncId
+    print_int: cranelift_module::FuncId,
+    print_float: cranelift_module::FuncId,
+    malloc: cranelift_module::FuncId,
+    free: cranelift_module::FuncId,
+}
+
+/// Runtime `print` for floats, used when running under the JIT.
+extern "C" fn print_float(f: f64) {
+    println!("{}", f);
 }
 
-fn tr_type(typ: &bril::Type) -> ir::Type {
+fn tr_type(typ: &bril::Type, ptr_type: ir::Type) -> ir::Type {
     match typ {
         bril::Type::Int => ir::types::I64,
         bril::Type::Bool => ir::types::B1,
+        bril::Type::Float => ir::types::F64,
+        bril::Type::Ptr(_) => ptr_type,
     }
 }
 
-fn tr_sig(func: &bril::Function) -> ir::Signature {
+fn tr_sig(func: &bril::Function, ptr_type: ir::Type) -> ir::Signature {
     let mut sig = ir::Signature::new(isa::CallConv::SystemV);
     if let Some(ret) = &func.return_type {
-        sig.returns.push(ir::AbiParam::new(tr_type(ret)));
+        sig.returns.push(ir::AbiParam::new(tr_type(ret, ptr_type)));
     }
     for arg in &func.args {
-        sig.params.push(ir::AbiParam::new(tr_type(&arg.arg_type)));
+        sig.params.push(ir::AbiParam::new(tr_type(&arg.arg_type, ptr_type)));
     }
     sig
 }
 
 fn all_vars(func: &bril::Function) -> HashMap<&String, &bril::Type> {
-    func.instrs.iter().filter_map(|inst| {
+    // Function parameters are variables too, even if they're never reassigned.
+    let args = func.args.iter().map(|arg| (&arg.name, &arg.arg_type));
+    let dests = func.instrs.iter().filter_map(|inst| {
         match inst {
             bril::Code::Instruction(op) => {
                 match op {
@@ -51,7 +74,8 @@ fn all_vars(func: &bril::Function) -> HashMap<&String, &bril::Type> {
             },
             _ => None
         }
-    }).collect()
+    });
+    args.chain(dests).collect()
 }
 
 struct Translator<M: Module> {
@@ -59,6 +83,7 @@ struct Translator<M: Module> {
     rt_funcs: RTIds,
     module: M,
     context: cranelift::codegen::Context,
+    funcs: HashMap<String, (cranelift_module::FuncId, ir::Signature)>,
 }
 
 impl Translator<ObjectModule> {
@@ -72,6 +97,7 @@ impl Translator<ObjectModule> {
             .unwrap();
         let mut module =
             ObjectModule::new(ObjectBuilder::new(isa, "foo", default_libcall_names()).unwrap());
+        let ptr_type = module.target_config().pointer_type();
 
         // Set up the runtime library.
         // TODO Maybe these should be hash tables or something?
@@ -80,31 +106,293 @@ impl Translator<ObjectModule> {
                 let mut sig = ir::Signature::new(isa::CallConv::SystemV);
                 sig.params.push(ir::AbiParam::new(ir::types::I64));
                 sig
-            }
+            },
+            print_float: {
+                let mut sig = ir::Signature::new(isa::CallConv::SystemV);
+                sig.params.push(ir::AbiParam::new(ir::types::F64));
+                sig
+            },
+            malloc: {
+                let mut sig = ir::Signature::new(isa::CallConv::SystemV);
+                sig.params.push(ir::AbiParam::new(ir::types::I64));
+                sig.returns.push(ir::AbiParam::new(ptr_type));
+                sig
+            },
+            free: {
+                let mut sig = ir::Signature::new(isa::CallConv::SystemV);
+                sig.params.push(ir::AbiParam::new(ptr_type));
+                sig
+            },
         };
         let rt_funcs = RTIds {
             print_int: {
                 module
                     .declare_function("print_int", cranelift_module::Linkage::Import, &rt_sigs.print_int)
                     .unwrap()
+            },
+            print_float: {
+                module
+                    .declare_function("print_float", cranelift_module::Linkage::Import, &rt_sigs.print_float)
+                    .unwrap()
+            },
+            malloc: {
+                module
+                    .declare_function("malloc", cranelift_module::Linkage::Import, &rt_sigs.malloc)
+                    .unwrap()
+            },
+            free: {
+                module
+                    .declare_function("free", cranelift_module::Linkage::Import, &rt_sigs.free)
+                    .unwrap()
+            },
+        };
+
+        let context = cranelift::codegen::Context::new();
+
+        Self {
+            rt_sigs,
+            rt_funcs,
+            module,
+            context,
+            funcs: HashMap::new(),
+        }
+    }
+
+    /// Synthesize a C-callable `main` entry point that parses `argc`/`argv`,
+    /// converts the arguments to the types declared on the Bril `main`, calls
+    /// it, and returns its result as the process exit code.
+    fn emit_main_shim(&mut self, main: &bril::Function) {
+        let ptr_type = self.module.target_config().pointer_type();
+
+        // `int main(int argc, char **argv)`.
+        let mut sig = ir::Signature::new(isa::CallConv::SystemV);
+        sig.params.push(ir::AbiParam::new(ir::types::I32));
+        sig.params.push(ir::AbiParam::new(ptr_type));
+        sig.returns.push(ir::AbiParam::new(ir::types::I32));
+
+        // libc parsers for turning argv strings into Bril values.
+        let mut atoll_sig = ir::Signature::new(isa::CallConv::SystemV);
+        atoll_sig.params.push(ir::AbiParam::new(ptr_type));
+        atoll_sig.returns.push(ir::AbiParam::new(ir::types::I64));
+        let atoll_id = self.module
+            .declare_function("atoll", cranelift_module::Linkage::Import, &atoll_sig)
+            .unwrap();
+
+        let mut atof_sig = ir::Signature::new(isa::CallConv::SystemV);
+        atof_sig.params.push(ir::AbiParam::new(ptr_type));
+        atof_sig.returns.push(ir::AbiParam::new(ir::types::F64));
+        let atof_id = self.module
+            .declare_function("atof", cranelift_module::Linkage::Import, &atof_sig)
+            .unwrap();
+
+        let mut fn_builder_ctx = FunctionBuilderContext::new();
+        let mut cl_func = ir::Function::with_name_signature(ir::ExternalName::user(0, 0), sig);
+        {
+            let mut builder = FunctionBuilder::new(&mut cl_func, &mut fn_builder_ctx);
+            let atoll = self.module.declare_func_in_func(atoll_id, builder.func);
+            let atof = self.module.declare_func_in_func(atof_id, builder.func);
+            let (main_id, _) = self.funcs.get(&main.name).unwrap().clone();
+            let bril_main = self.module.declare_func_in_func(main_id, builder.func);
+
+            let block = builder.create_block();
+            builder.append_block_params_for_function_params(block);
+            builder.switch_to_block(block);
+            builder.seal_block(block);
+
+            // argv[0] is the program name, so the i-th Bril arg is argv[i + 1].
+            let argv = builder.block_params(block)[1];
+            let ptr_size = ptr_type.bytes() as i32;
+            let mut arg_vals = Vec::new();
+            for (i, arg) in main.args.iter().enumerate() {
+                let offset = (i as i32 + 1) * ptr_size;
+                let slot = builder.ins().load(ptr_type, MemFlags::new(), argv, offset);
+                let val = match arg.arg_type {
+                    bril::Type::Float => {
+                        let call = builder.ins().call(atof, &[slot]);
+                        builder.inst_results(call)[0]
+                    },
+                    bril::Type::Bool => {
+                        let call = builder.ins().call(atoll, &[slot]);
+                        let int = builder.inst_results(call)[0];
+                        builder.ins().icmp_imm(IntCC::NotEqual, int, 0)
+                    },
+                    _ => {
+                        let call = builder.ins().call(atoll, &[slot]);
+                        builder.inst_results(call)[0]
+                    },
+                };
+                arg_vals.push(val);
             }
+
+            let call = builder.ins().call(bril_main, &arg_vals);
+            let ret = if main.return_type.is_some() {
+                let res = builder.inst_results(call)[0];
+                builder.ins().ireduce(ir::types::I32, res)
+            } else {
+                builder.ins().iconst(ir::types::I32, 0)
+            };
+            builder.ins().return_(&[ret]);
+            builder.finalize();
+        }
+
+        let shim_id = self.module
+            .declare_function("main", cranelift_module::Linkage::Export, &cl_func.signature)
+            .unwrap();
+        self.context.func = cl_func;
+        self.module.define_function(shim_id, &mut self.context).unwrap();
+        self.context.clear();
+    }
+
+    /// Finish the object module and write the linkable object to `path`.
+    fn emit(self, path: &str) {
+        let product = self.module.finish();
+        let bytes = product.emit().unwrap();
+        std::fs::write(path, bytes).unwrap();
+    }
+}
+
+impl Translator<JITModule> {
+    fn new() -> Self {
+        // Make a JIT module, wiring the runtime symbols into this process.
+        let flag_builder = settings::builder();
+        let isa_builder = cranelift_native::builder().unwrap();
+        let isa = isa_builder
+            .finish(settings::Flags::new(flag_builder))
+            .unwrap();
+        let mut builder = JITBuilder::with_isa(isa, default_libcall_names());
+        builder.symbol("print_int", print_int as *const u8);
+        builder.symbol("print_float", print_float as *const u8);
+        let mut module = JITModule::new(builder);
+        let ptr_type = module.target_config().pointer_type();
+
+        // Set up the runtime library.
+        let rt_sigs = RTSigs {
+            print_int: {
+                let mut sig = ir::Signature::new(isa::CallConv::SystemV);
+                sig.params.push(ir::AbiParam::new(ir::types::I64));
+                sig
+            },
+            print_float: {
+                let mut sig = ir::Signature::new(isa::CallConv::SystemV);
+                sig.params.push(ir::AbiParam::new(ir::types::F64));
+                sig
+            },
+            malloc: {
+                let mut sig = ir::Signature::new(isa::CallConv::SystemV);
+                sig.params.push(ir::AbiParam::new(ir::types::I64));
+                sig.returns.push(ir::AbiParam::new(ptr_type));
+                sig
+            },
+            free: {
+                let mut sig = ir::Signature::new(isa::CallConv::SystemV);
+                sig.params.push(ir::AbiParam::new(ptr_type));
+                sig
+            },
+        };
+        let rt_funcs = RTIds {
+            print_int: {
+                module
+                    .declare_function("print_int", cranelift_module::Linkage::Import, &rt_sigs.print_int)
+                    .unwrap()
+            },
+            print_float: {
+                module
+                    .declare_function("print_float", cranelift_module::Linkage::Import, &rt_sigs.print_float)
+                    .unwrap()
+            },
+            malloc: {
+                module
+                    .declare_function("malloc", cranelift_module::Linkage::Import, &rt_sigs.malloc)
+                    .unwrap()
+            },
+            free: {
+                module
+                    .declare_function("free", cranelift_module::Linkage::Import, &rt_sigs.free)
+                    .unwrap()
+            },
         };
 
         let context = cranelift::codegen::Context::new();
-        
+
         Self {
             rt_sigs,
             rt_funcs,
             module,
             context,
+            funcs: HashMap::new(),
+        }
+    }
+
+    /// Finalize all definitions and immediately run the Bril `main`, returning
+    /// its result as a process exit code.
+    fn run(mut self, main: &bril::Function) -> i32 {
+        self.module.finalize_definitions();
+
+        let (main_id, _) = self.funcs.get(&main.name).unwrap().clone();
+        let code = self.module.get_finalized_function(main_id);
+
+        // The JIT entry only knows how to marshal integer arguments and
+        // results, so reject any other `main` signature rather than invoking it
+        // with the wrong ABI.
+        if !main.args.iter().all(|arg| matches!(arg.arg_type, bril::Type::Int)) {
+            panic!("JIT only supports integer arguments to main");
+        }
+        if !matches!(main.return_type, None | Some(bril::Type::Int)) {
+            panic!("JIT only supports an integer or void return from main");
+        }
+
+        // Gather integer arguments for `main` from the command line.
+        let args: Vec<i64> = std::env::args()
+            .skip(1)
+            .filter(|a| a != "--jit")
+            .map(|a| a.parse().unwrap())
+            .collect();
+
+        let ret: i64 = unsafe {
+            match main.args.len() {
+                0 => {
+                    let f = std::mem::transmute::<_, extern "C" fn() -> i64>(code);
+                    f()
+                },
+                1 => {
+                    let f = std::mem::transmute::<_, extern "C" fn(i64) -> i64>(code);
+                    f(args[0])
+                },
+                2 => {
+                    let f = std::mem::transmute::<_, extern "C" fn(i64, i64) -> i64>(code);
+                    f(args[0], args[1])
+                },
+                n => panic!("unsupported JIT argument count: {}", n),
+            }
+        };
+
+        if main.return_type.is_some() {
+            ret as i32
+        } else {
+            0
         }
     }
 }
 
 impl<M: Module> Translator<M> {
+    /// Declaration pass: give every Bril function a Cranelift declaration so
+    /// that calls can be resolved before any body is compiled.
+    fn declare_func(&mut self, func: &bril::Function) {
+        let ptr_type = self.module.target_config().pointer_type();
+        let sig = tr_sig(func, ptr_type);
+        // Declare the Bril `main` under a distinct symbol so it doesn't collide
+        // with the C-callable `main` entry shim.
+        let symbol = if func.name == "main" { "bril_main" } else { &func.name };
+        let id = self.module
+            .declare_function(symbol, cranelift_module::Linkage::Export, &sig)
+            .unwrap();
+        self.funcs.insert(func.name.clone(), (id, sig));
+    }
+
     fn compile_func(&mut self, func: bril::Function) -> ir::Function {
         // Build function signature.
-        let sig = tr_sig(&func);
+        let ptr_type = self.module.target_config().pointer_type();
+        let sig = tr_sig(&func, ptr_type);
 
         // Create the function.
         // TODO Do something about the name.
@@ -117,22 +405,56 @@ impl<M: Module> Translator<M> {
 
             // Declare runtime functions.
             let print_int = self.module.declare_func_in_func(self.rt_funcs.print_int, builder.func);
+            let print_float = self.module.declare_func_in_func(self.rt_funcs.print_float, builder.func);
+            let malloc = self.module.declare_func_in_func(self.rt_funcs.malloc, builder.func);
+            let free = self.module.declare_func_in_func(self.rt_funcs.free, builder.func);
 
-            // Declare all variables.
+            // Declare all variables, remembering each pointer's element type so
+            // that `load` and `ptradd` know the pointee width.
+            let types = all_vars(&func);
             let mut vars = HashMap::<&String, Variable>::new();
-            for (i, (name, typ)) in all_vars(&func).iter().enumerate() {
+            let mut elem_types = HashMap::<&String, ir::Type>::new();
+            for (i, (name, typ)) in types.iter().enumerate() {
                 let var = Variable::new(i);
-                builder.declare_var(var, tr_type(typ));
+                builder.declare_var(var, tr_type(typ, ptr_type));
                 vars.insert(name, var);
+                if let bril::Type::Ptr(inner) = typ {
+                    elem_types.insert(name, tr_type(inner, ptr_type));
+                }
             }
 
-            // TODO just one block for now...
-            let block = builder.create_block();
-            builder.switch_to_block(block);
+            // Pre-pass: one block per Bril label, plus an entry block.
+            let entry_block = builder.create_block();
+            builder.append_block_params_for_function_params(entry_block);
+            let mut blocks = HashMap::<&String, ir::Block>::new();
+            for code in &func.instrs {
+                if let bril::Code::Label { label } = code {
+                    blocks.insert(label, builder.create_block());
+                }
+            }
 
-            // Insert instructions.
+            // Bind the function arguments from the entry block's parameters.
+            builder.switch_to_block(entry_block);
+            for (i, arg) in func.args.iter().enumerate() {
+                let var = vars.get(&arg.name).unwrap();
+                let param = builder.block_params(entry_block)[i];
+                builder.def_var(*var, param);
+            }
+
+            // Walk the instruction stream, splitting into basic blocks at each
+            // label. `terminated` tracks whether the current block already ends
+            // in a control-flow instruction; if not, we fall through.
+            let mut terminated = false;
             for code in &func.instrs {
                 match code {
+                    bril::Code::Label { label } => {
+                        let next_block = blocks[label];
+                        if !terminated {
+                            builder.ins().jump(next_block, &[]);
+                        }
+                        builder.switch_to_block(next_block);
+                        terminated = false;
+                    },
                     bril::Code::Instruction(inst) => {
                         match inst {
                             bril::Instruction::Constant { dest, op: _, const_type: _, value } => {
@@ -140,31 +462,224 @@ impl<M: Module> Translator<M> {
                                 let val = match value {
                                     bril::Literal::Int(i) => builder.ins().iconst(ir::types::I64, *i),
                                     bril::Literal::Bool(b) => builder.ins().bconst(ir::types::B1, *b),
+                                    bril::Literal::Float(f) => builder.ins().f64const(*f),
                                 };
                                 builder.def_var(*var, val);
                             },
-                            bril::Instruction::Effect { args, funcs: _, labels: _, op } => {
+                            bril::Instruction::Value { args, dest, funcs, labels: _, op, op_type: _ } => {
+                                let var = vars.get(&dest).unwrap();
+                                let val = match op {
+                                    bril::ValueOps::Call => {
+                                        let (callee_id, _) = self.funcs.get(&funcs[0]).unwrap().clone();
+                                        let callee = self.module.declare_func_in_func(callee_id, builder.func);
+                                        let arg_vals: Vec<ir::Value> = args.iter()
+                                            .map(|a| builder.use_var(*vars.get(a).unwrap()))
+                                            .collect();
+                                        let call = builder.ins().call(callee, &arg_vals);
+                                        builder.inst_results(call)[0]
+                                    },
+                                    bril::ValueOps::Add => {
+                                        let a = builder.use_var(*vars.get(&args[0]).unwrap());
+                                        let b = builder.use_var(*vars.get(&args[1]).unwrap());
+                                        builder.ins().iadd(a, b)
+                                    },
+                                    bril::ValueOps::Sub => {
+                                        let a = builder.use_var(*vars.get(&args[0]).unwrap());
+                                        let b = builder.use_var(*vars.get(&args[1]).unwrap());
+                                        builder.ins().isub(a, b)
+                                    },
+                                    bril::ValueOps::Mul => {
+                                        let a = builder.use_var(*vars.get(&args[0]).unwrap());
+                                        let b = builder.use_var(*vars.get(&args[1]).unwrap());
+                                        builder.ins().imul(a, b)
+                                    },
+                                    bril::ValueOps::Div => {
+                                        let a = builder.use_var(*vars.get(&args[0]).unwrap());
+                                        let b = builder.use_var(*vars.get(&args[1]).unwrap());
+                                        builder.ins().sdiv(a, b)
+                                    },
+                                    bril::ValueOps::Eq => {
+                                        let a = builder.use_var(*vars.get(&args[0]).unwrap());
+                                        let b = builder.use_var(*vars.get(&args[1]).unwrap());
+                                        builder.ins().icmp(IntCC::Equal, a, b)
+                                    },
+                                    bril::ValueOps::Lt => {
+                                        let a = builder.use_var(*vars.get(&args[0]).unwrap());
+                                        let b = builder.use_var(*vars.get(&args[1]).unwrap());
+                                        builder.ins().icmp(IntCC::SignedLessThan, a, b)
+                                    },
+                                    bril::ValueOps::Gt => {
+                                        let a = builder.use_var(*vars.get(&args[0]).unwrap());
+                                        let b = builder.use_var(*vars.get(&args[1]).unwrap());
+                                        builder.ins().icmp(IntCC::SignedGreaterThan, a, b)
+                                    },
+                                    bril::ValueOps::Le => {
+                                        let a = builder.use_var(*vars.get(&args[0]).unwrap());
+                                        let b = builder.use_var(*vars.get(&args[1]).unwrap());
+                                        builder.ins().icmp(IntCC::SignedLessThanOrEqual, a, b)
+                                    },
+                                    bril::ValueOps::Ge => {
+                                        let a = builder.use_var(*vars.get(&args[0]).unwrap());
+                                        let b = builder.use_var(*vars.get(&args[1]).unwrap());
+                                        builder.ins().icmp(IntCC::SignedGreaterThanOrEqual, a, b)
+                                    },
+                                    bril::ValueOps::And => {
+                                        let a = builder.use_var(*vars.get(&args[0]).unwrap());
+                                        let b = builder.use_var(*vars.get(&args[1]).unwrap());
+                                        builder.ins().band(a, b)
+                                    },
+                                    bril::ValueOps::Or => {
+                                        let a = builder.use_var(*vars.get(&args[0]).unwrap());
+                                        let b = builder.use_var(*vars.get(&args[1]).unwrap());
+                                        builder.ins().bor(a, b)
+                                    },
+                                    bril::ValueOps::Not => {
+                                        let a = builder.use_var(*vars.get(&args[0]).unwrap());
+                                        builder.ins().bnot(a)
+                                    },
+                                    bril::ValueOps::Id => {
+                                        builder.use_var(*vars.get(&args[0]).unwrap())
+                                    },
+                                    bril::ValueOps::Fadd => {
+                                        let a = builder.use_var(*vars.get(&args[0]).unwrap());
+                                        let b = builder.use_var(*vars.get(&args[1]).unwrap());
+                                        builder.ins().fadd(a, b)
+                                    },
+                                    bril::ValueOps::Fsub => {
+                                        let a = builder.use_var(*vars.get(&args[0]).unwrap());
+                                        let b = builder.use_var(*vars.get(&args[1]).unwrap());
+                                        builder.ins().fsub(a, b)
+                                    },
+                                    bril::ValueOps::Fmul => {
+                                        let a = builder.use_var(*vars.get(&args[0]).unwrap());
+                                        let b = builder.use_var(*vars.get(&args[1]).unwrap());
+                                        builder.ins().fmul(a, b)
+                                    },
+                                    bril::ValueOps::Fdiv => {
+                                        let a = builder.use_var(*vars.get(&args[0]).unwrap());
+                                        let b = builder.use_var(*vars.get(&args[1]).unwrap());
+                                        builder.ins().fdiv(a, b)
+                                    },
+                                    bril::ValueOps::Feq => {
+                                        let a = builder.use_var(*vars.get(&args[0]).unwrap());
+                                        let b = builder.use_var(*vars.get(&args[1]).unwrap());
+                                        builder.ins().fcmp(FloatCC::Equal, a, b)
+                                    },
+                                    bril::ValueOps::Flt => {
+                                        let a = builder.use_var(*vars.get(&args[0]).unwrap());
+                                        let b = builder.use_var(*vars.get(&args[1]).unwrap());
+                                        builder.ins().fcmp(FloatCC::LessThan, a, b)
+                                    },
+                                    bril::ValueOps::Fgt => {
+                                        let a = builder.use_var(*vars.get(&args[0]).unwrap());
+                                        let b = builder.use_var(*vars.get(&args[1]).unwrap());
+                                        builder.ins().fcmp(FloatCC::GreaterThan, a, b)
+                                    },
+                                    bril::ValueOps::Fle => {
+                                        let a = builder.use_var(*vars.get(&args[0]).unwrap());
+                                        let b = builder.use_var(*vars.get(&args[1]).unwrap());
+                                        builder.ins().fcmp(FloatCC::LessThanOrEqual, a, b)
+                                    },
+                                    bril::ValueOps::Fge => {
+                                        let a = builder.use_var(*vars.get(&args[0]).unwrap());
+                                        let b = builder.use_var(*vars.get(&args[1]).unwrap());
+                                        builder.ins().fcmp(FloatCC::GreaterThanOrEqual, a, b)
+                                    },
+                                    bril::ValueOps::Alloc => {
+                                        let count = builder.use_var(*vars.get(&args[0]).unwrap());
+                                        let elem = elem_types.get(&dest).unwrap();
+                                        let size = builder.ins().iconst(ir::types::I64, elem.bytes() as i64);
+                                        let bytes = builder.ins().imul(count, size);
+                                        let call = builder.ins().call(malloc, &[bytes]);
+                                        builder.inst_results(call)[0]
+                                    },
+                                    bril::ValueOps::Load => {
+                                        let addr = builder.use_var(*vars.get(&args[0]).unwrap());
+                                        let ty = *elem_types.get(&args[0]).unwrap();
+                                        builder.ins().load(ty, MemFlags::new(), addr, 0)
+                                    },
+                                    bril::ValueOps::PtrAdd => {
+                                        let base = builder.use_var(*vars.get(&args[0]).unwrap());
+                                        let index = builder.use_var(*vars.get(&args[1]).unwrap());
+                                        let elem = elem_types.get(&dest).unwrap();
+                                        let size = builder.ins().iconst(ir::types::I64, elem.bytes() as i64);
+                                        let offset = builder.ins().imul(index, size);
+                                        builder.ins().iadd(base, offset)
+                                    },
+                                    _ => panic!("unimplemented value op: {:?}", op),
+                                };
+                                builder.def_var(*var, val);
+                            },
+                            bril::Instruction::Effect { args, funcs, labels, op } => {
                                 match op {
+                                    bril::EffectOps::Call => {
+                                        let (callee_id, _) = self.funcs.get(&funcs[0]).unwrap().clone();
+                                        let callee = self.module.declare_func_in_func(callee_id, builder.func);
+                                        let arg_vals: Vec<ir::Value> = args.iter()
+                                            .map(|a| builder.use_var(*vars.get(a).unwrap()))
+                                            .collect();
+                                        builder.ins().call(callee, &arg_vals);
+                                    },
                                     bril::EffectOps::Print => {
-                                        // TODO Target should depend on the type.
                                         // TODO Deal with multiple args somehow.
                                         let var = vars.get(&args[0]).unwrap();
                                         let arg = builder.use_var(*var);
-                                        builder.ins().call(print_int, &[arg]);
+                                        match types.get(&args[0]).unwrap() {
+                                            bril::Type::Float => { builder.ins().call(print_float, &[arg]); },
+                                            _ => { builder.ins().call(print_int, &[arg]); },
+                                        }
+                                    },
+                                    bril::EffectOps::Jump => {
+                                        builder.ins().jump(blocks[&labels[0]], &[]);
+                                        terminated = true;
+                                    },
+                                    bril::EffectOps::Branch => {
+                                        let var = vars.get(&args[0]).unwrap();
+                                        let cond = builder.use_var(*var);
+                                        builder.ins().brnz(cond, blocks[&labels[0]], &[]);
+                                        builder.ins().jump(blocks[&labels[1]], &[]);
+                                        terminated = true;
+                                    },
+                                    bril::EffectOps::Store => {
+                                        let addr = builder.use_var(*vars.get(&args[0]).unwrap());
+                                        let val = builder.use_var(*vars.get(&args[1]).unwrap());
+                                        builder.ins().store(MemFlags::new(), val, addr, 0);
+                                    },
+                                    bril::EffectOps::Free => {
+                                        let addr = builder.use_var(*vars.get(&args[0]).unwrap());
+                                        builder.ins().call(free, &[addr]);
                                     },
-                                    _ => todo!(),
+                                    bril::EffectOps::Return => {
+                                        match args.first() {
+                                            Some(arg) => {
+                                                let var = vars.get(arg).unwrap();
+                                                let val = builder.use_var(*var);
+                                                builder.ins().return_(&[val]);
+                                            },
+                                            None => {
+                                                builder.ins().return_(&[]);
+                                            },
+                                        }
+                                        terminated = true;
+                                    },
+                                    _ => panic!("unimplemented effect op: {:?}", op),
                                 }
                             },
                             _ => (),  // TODO
                         }
                     },
-                    _ => (),  // TODO
                 }
             }
 
-            builder.ins().return_(&[]);  // TODO
-            builder.seal_block(block);
-            
+            // A final fall-through block with no explicit terminator returns.
+            if !terminated {
+                builder.ins().return_(&[]);
+            }
+
+            // Bril allows forward jumps, so we can only seal once every block's
+            // predecessors are known.
+            builder.seal_all_blocks();
+
             builder.finalize();
         }
 
@@ -176,26 +691,55 @@ impl<M: Module> Translator<M> {
             panic!("{}", errors);
         }
 
-        // Add to the module.
-        // TODO Move to a separate function?
-        let func_id = self.module
-            .declare_function(&func.name, cranelift_module::Linkage::Export, &cl_func.signature)
-            .unwrap();
+        // Add to the module, using the id from the declaration pass.
+        let (func_id, _) = self.funcs.get(&func.name).unwrap();
+        let func_id = *func_id;
+        self.context.func = cl_func;
         self.module
             .define_function(func_id, &mut self.context)
             .unwrap();
-        
+        let cl_func = self.context.func.clone();
+        self.context.clear();
+
         cl_func
     }
 }
 
 fn main() {
+    let jit = std::env::args().any(|a| a == "--jit");
+
     // Load the Bril program from stdin.
     let prog = bril::load_program();
-    
-    let mut trans = Translator::<ObjectModule>::new();
-    
-    for bril_func in prog.functions {
-        trans.compile_func(bril_func);
+
+    // Keep the Bril `main` signature around for the entry point.
+    let main_func = prog.functions.iter().find(|f| f.name == "main").cloned();
+
+    if jit {
+        // JIT path: compile and run in-process.
+        let mut trans = Translator::<JITModule>::new();
+        for bril_func in &prog.functions {
+            trans.declare_func(bril_func);
+        }
+        for bril_func in prog.functions {
+            trans.compile_func(bril_func);
+        }
+        let code = match &main_func {
+            Some(main) => trans.run(main),
+            None => 0,
+        };
+        std::process::exit(code);
+    } else {
+        // Object path: emit a linkable `.o` plus a C-callable entry shim.
+        let mut trans = Translator::<ObjectModule>::new();
+        for bril_func in &prog.functions {
+            trans.declare_func(bril_func);
+        }
+        for bril_func in prog.functions {
+            trans.compile_func(bril_func);
+        }
+        if let Some(main) = &main_func {
+            trans.emit_main_shim(main);
+        }
+        trans.emit("out.o");
     }
 }